@@ -0,0 +1,201 @@
+//! Structured diagnostics for schema/instance parse failures.
+//!
+//! `serde_json`, `serde_yaml`, and `json5` each report parse failures with
+//! their own opaque `Display` impl, discarding the input name and reducing
+//! "this is the third document in this file's NDJSON stream" down to
+//! something like "EOF while parsing a value at line 1 column 1". A
+//! [`Diagnostic`] re-attaches that context, and classifies the failure into
+//! one of a few common scenarios so it reads as actionable rather than as a
+//! raw library error.
+
+use std::fmt;
+
+/// A coarse classification of why a document failed to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The input ended before a complete document was read.
+    Truncated,
+    /// There was unparsed content left over after a document.
+    TrailingData,
+    /// The document parsed, but didn't match the expected shape.
+    TypeMismatch,
+    /// The input wasn't valid UTF-8.
+    InvalidUtf8,
+    /// Some other syntax error.
+    Syntax,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Truncated => "truncated input",
+            Kind::TrailingData => "trailing data",
+            Kind::TypeMismatch => "type mismatch",
+            Kind::InvalidUtf8 => "invalid UTF-8",
+            Kind::Syntax => "syntax error",
+        })
+    }
+}
+
+/// A parse failure, attributed to a specific input and (for streamed
+/// inputs, like NDJSON or multi-document YAML) a specific document within
+/// it.
+#[derive(Debug)]
+pub struct Diagnostic {
+    input: String,
+    document_index: Option<usize>,
+    line: Option<usize>,
+    column: Option<usize>,
+    kind: Kind,
+    message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.input)?;
+
+        if let Some(document_index) = self.document_index {
+            write!(f, " (document {})", document_index)?;
+        }
+
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ", line {}, column {}", line, column)?;
+        }
+
+        write!(f, ": {}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl Diagnostic {
+    pub fn from_json(input: &str, document_index: Option<usize>, err: &serde_json::Error) -> Self {
+        let kind = if err.is_eof() {
+            Kind::Truncated
+        } else if err.is_data() {
+            Kind::TypeMismatch
+        } else if err.to_string().contains("trailing characters") {
+            Kind::TrailingData
+        } else {
+            Kind::Syntax
+        };
+
+        Diagnostic {
+            input: input.to_owned(),
+            document_index,
+            line: Some(err.line()),
+            column: Some(err.column()),
+            kind,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn from_yaml(input: &str, document_index: Option<usize>, err: &serde_yaml::Error) -> Self {
+        let location = err.location();
+
+        Diagnostic {
+            input: input.to_owned(),
+            document_index,
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+            kind: Kind::Syntax,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn from_json5(input: &str, err: &json5::Error) -> Self {
+        let json5::Error::Message { msg, location } = err;
+
+        Diagnostic {
+            input: input.to_owned(),
+            document_index: None,
+            line: location.as_ref().map(|location| location.line),
+            column: location.as_ref().map(|location| location.column),
+            kind: if msg.contains("trailing") {
+                Kind::TrailingData
+            } else {
+                Kind::Syntax
+            },
+            message: msg.clone(),
+        }
+    }
+
+    pub fn invalid_utf8(input: &str, document_index: Option<usize>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            input: input.to_owned(),
+            document_index,
+            line: None,
+            column: None,
+            kind: Kind::InvalidUtf8,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_json_classifies_truncated_input() {
+        let err = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let diagnostic = Diagnostic::from_json("schema.json", None, &err);
+
+        assert_eq!(diagnostic.kind, Kind::Truncated);
+        assert!(diagnostic.to_string().starts_with("schema.json, line"));
+    }
+
+    #[test]
+    fn from_json_classifies_type_mismatch_and_includes_document_index() {
+        let err = serde_json::from_str::<bool>("1").unwrap_err();
+        let diagnostic = Diagnostic::from_json("instances.ndjson", Some(2), &err);
+
+        assert_eq!(diagnostic.kind, Kind::TypeMismatch);
+        assert!(diagnostic.to_string().starts_with("instances.ndjson (document 2),"));
+    }
+
+    #[test]
+    fn from_json_classifies_trailing_data() {
+        let err = serde_json::from_str::<serde_json::Value>("{} {}").unwrap_err();
+        let diagnostic = Diagnostic::from_json("schema.json", None, &err);
+
+        assert_eq!(diagnostic.kind, Kind::TrailingData);
+    }
+
+    #[test]
+    fn from_yaml_carries_line_and_column_when_available() {
+        let err = serde_yaml::from_str::<serde_json::Value>("[").unwrap_err();
+        let diagnostic = Diagnostic::from_yaml("schema.yaml", None, &err);
+        let location = err.location();
+
+        assert_eq!(diagnostic.kind, Kind::Syntax);
+        assert_eq!(diagnostic.line, location.as_ref().map(|location| location.line()));
+        assert_eq!(diagnostic.column, location.as_ref().map(|location| location.column()));
+        assert!(diagnostic.to_string().starts_with("schema.yaml"));
+    }
+
+    #[test]
+    fn from_json5_carries_location_and_message() {
+        let err = json5::from_str::<serde_json::Value>("{} {}").unwrap_err();
+        let diagnostic = Diagnostic::from_json5("schema.json5", &err);
+
+        assert_eq!(diagnostic.kind, Kind::Syntax);
+        assert_eq!(diagnostic.line, Some(1));
+        assert!(diagnostic.to_string().starts_with("schema.json5, line 1, column"));
+    }
+
+    #[test]
+    fn display_includes_document_index_only_when_present() {
+        let with_index = Diagnostic::invalid_utf8("a.ndjson", Some(3), "invalid byte");
+        assert_eq!(
+            with_index.to_string(),
+            "a.ndjson (document 3): invalid UTF-8: invalid byte"
+        );
+
+        let without_index = Diagnostic::invalid_utf8("schema.json", None, "invalid byte");
+        assert_eq!(
+            without_index.to_string(),
+            "schema.json: invalid UTF-8: invalid byte"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! Support for reading schemas and instances in formats other than plain
+//! JSON.
+
+use crate::diagnostics::Diagnostic;
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+/// An input format that can be deserialized into `serde_json::Value`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Json5,
+    Yaml,
+}
+
+impl Format {
+    /// Parses a format name, such as one given on the command line. "auto"
+    /// is not a `Format` in its own right -- callers should resolve it with
+    /// `Format::from_file_name` first.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "json" => Ok(Format::Json),
+            "json5" => Ok(Format::Json5),
+            "yaml" => Ok(Format::Yaml),
+            other => anyhow::bail!("Unrecognized format: {}", other),
+        }
+    }
+
+    /// Guesses a format from a file name's extension. Falls back to JSON for
+    /// "-" (standard input) or an unrecognized extension, so that `auto`
+    /// behaves the same way the tool always has for plain JSON input.
+    pub fn from_file_name(file_name: &str) -> Self {
+        match Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("json5") => Format::Json5,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Parses a single `serde_json::Value` out of `reader`, such as a schema.
+/// `input_name` is attributed to any parse diagnostic ("-" or a file name).
+pub fn parse_single(format: Format, input_name: &str, mut reader: impl Read) -> Result<Value> {
+    match format {
+        Format::Json => serde_json::from_reader(reader)
+            .map_err(|err| Diagnostic::from_json(input_name, None, &err).into()),
+        Format::Json5 => {
+            let contents = read_to_string(input_name, &mut reader)?;
+            json5::from_str(&contents)
+                .map_err(|err| Diagnostic::from_json5(input_name, &err).into())
+        }
+        Format::Yaml => serde_yaml::from_reader(reader)
+            .map_err(|err| Diagnostic::from_yaml(input_name, None, &err).into()),
+    }
+}
+
+/// Parses a stream of `serde_json::Value`s out of `reader`, such as a file of
+/// instances to validate. Yields `(document_index, result)` pairs, where
+/// `document_index` is the zero-based position of that document within
+/// `input_name`'s stream.
+///
+/// JSON is read as newline-delimited JSON, matching the tool's historical
+/// behavior. YAML is read as a sequence of "---"-separated documents. JSON5
+/// has no standard notion of a document stream, so it's read as a single
+/// document.
+pub fn parse_stream<'a>(
+    format: Format,
+    input_name: &'a str,
+    mut reader: impl Read + 'a,
+) -> Result<Box<dyn Iterator<Item = (usize, Result<Value>)> + 'a>> {
+    match format {
+        Format::Json => Ok(Box::new(
+            serde_json::Deserializer::from_reader(reader)
+                .into_iter::<Value>()
+                .enumerate()
+                .map(move |(index, result)| {
+                    (
+                        index,
+                        result.map_err(|err| {
+                            Diagnostic::from_json(input_name, Some(index), &err).into()
+                        }),
+                    )
+                }),
+        )),
+        Format::Yaml => Ok(Box::new(
+            serde_yaml::Deserializer::from_reader(reader)
+                .enumerate()
+                .map(move |(index, document)| {
+                    (
+                        index,
+                        Value::deserialize(document).map_err(|err| {
+                            Diagnostic::from_yaml(input_name, Some(index), &err).into()
+                        }),
+                    )
+                }),
+        )),
+        Format::Json5 => {
+            let contents = read_to_string(input_name, &mut reader)?;
+            let value = json5::from_str(&contents)
+                .map_err(|err| Diagnostic::from_json5(input_name, &err).into());
+            Ok(Box::new(std::iter::once((0, value))))
+        }
+    }
+}
+
+/// Parses a newline-delimited JSON stream out of `reader`, pairing each
+/// `Value` with the exact source text it was parsed from (surrounding
+/// whitespace trimmed). Unlike `parse_stream`, which only hands back the
+/// deserialized `Value`, this lets a caller like `--filter` mode echo a
+/// valid instance back out byte-for-byte instead of re-serializing it,
+/// which would reorder object keys and renormalize number formatting.
+pub fn parse_json_stream_with_source<'a>(
+    input_name: &'a str,
+    reader: impl Read + 'a,
+) -> impl Iterator<Item = (usize, Result<(Value, String)>)> + 'a {
+    let captured = Rc::new(RefCell::new(Vec::new()));
+    let counting = CountingReader {
+        inner: reader,
+        captured: Rc::clone(&captured),
+    };
+    let mut stream = serde_json::Deserializer::from_reader(counting).into_iter::<Value>();
+    let mut consumed = 0;
+    let mut index = 0;
+
+    std::iter::from_fn(move || {
+        let result = stream.next()?;
+        let end = stream.byte_offset();
+        let start = consumed;
+        consumed = end;
+
+        let document_index = index;
+        index += 1;
+
+        let item = result
+            .map_err(|err| anyhow::Error::new(Diagnostic::from_json(input_name, Some(document_index), &err)))
+            .and_then(|value| {
+                let source = std::str::from_utf8(&captured.borrow()[start..end])
+                    .map_err(|err| {
+                        anyhow::Error::new(Diagnostic::invalid_utf8(
+                            input_name,
+                            Some(document_index),
+                            err.to_string(),
+                        ))
+                    })?
+                    .trim()
+                    .to_owned();
+                Ok((value, source))
+            });
+
+        Some((document_index, item))
+    })
+}
+
+/// A `Read` adapter that records every byte it yields, so a caller can
+/// later slice out the exact source text behind a deserialized value.
+struct CountingReader<R> {
+    inner: R,
+    captured: Rc<RefCell<Vec<u8>>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.captured.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn read_to_string(input_name: &str, reader: &mut impl Read) -> Result<String> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).map_err(|err| {
+        if err.kind() == ErrorKind::InvalidData {
+            anyhow::Error::new(Diagnostic::invalid_utf8(input_name, None, err.to_string()))
+        } else {
+            anyhow::Error::new(err).context(format!("Failed to read {}", input_name))
+        }
+    })?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Each instance file's stream is its own zero-based index sequence, so
+    /// that e.g. the second instance of the second file is reported as
+    /// index 1, not as a running count across every file given on the
+    /// command line.
+    #[test]
+    fn parse_stream_indices_restart_at_zero_per_call() {
+        let first: Vec<_> = parse_stream(Format::Json, "a.ndjson", "1\n2\n3\n".as_bytes())
+            .unwrap()
+            .map(|(index, result)| (index, result.unwrap()))
+            .collect();
+        assert_eq!(
+            first,
+            vec![(0, json!(1)), (1, json!(2)), (2, json!(3))]
+        );
+
+        let second: Vec<_> = parse_stream(Format::Json, "b.ndjson", "4\n5\n".as_bytes())
+            .unwrap()
+            .map(|(index, result)| (index, result.unwrap()))
+            .collect();
+        assert_eq!(second, vec![(0, json!(4)), (1, json!(5))]);
+    }
+
+    /// A parse failure partway through a file's stream is attributed to
+    /// that document's own index, not the index of the file's first
+    /// document or some running total.
+    #[test]
+    fn parse_stream_attributes_errors_to_their_own_document_index() {
+        let results: Vec<_> = parse_stream(Format::Json, "a.ndjson", "1\n[\n".as_bytes())
+            .unwrap()
+            .map(|(index, result)| (index, result.is_ok()))
+            .collect();
+
+        assert_eq!(results, vec![(0, true), (1, false)]);
+    }
+
+    #[test]
+    fn from_name_and_from_file_name_resolve_yaml_and_json5() {
+        assert_eq!(Format::from_name("yaml").unwrap(), Format::Yaml);
+        assert_eq!(Format::from_name("json5").unwrap(), Format::Json5);
+        assert!(Format::from_name("toml").is_err());
+
+        assert_eq!(Format::from_file_name("schema.yaml"), Format::Yaml);
+        assert_eq!(Format::from_file_name("schema.yml"), Format::Yaml);
+        assert_eq!(Format::from_file_name("schema.json5"), Format::Json5);
+        assert_eq!(Format::from_file_name("schema.json"), Format::Json);
+        assert_eq!(Format::from_file_name("-"), Format::Json);
+    }
+
+    #[test]
+    fn parse_single_reads_yaml_and_json5() {
+        let yaml = parse_single(Format::Yaml, "schema.yaml", "properties:\n  ok:\n    type: boolean\n".as_bytes())
+            .unwrap();
+        assert_eq!(yaml, json!({"properties": {"ok": {"type": "boolean"}}}));
+
+        let json5 =
+            parse_single(Format::Json5, "schema.json5", "{properties: {ok: {type: 'boolean'}}}".as_bytes())
+                .unwrap();
+        assert_eq!(json5, json!({"properties": {"ok": {"type": "boolean"}}}));
+    }
+
+    /// YAML's "---"-separated documents form a stream, the same way NDJSON
+    /// does; JSON5 has no such notion, so a JSON5 input is always exactly
+    /// one document.
+    #[test]
+    fn parse_stream_reads_multi_document_yaml_and_single_document_json5() {
+        let yaml: Vec<_> = parse_stream(Format::Yaml, "instances.yaml", "ok: true\n---\nok: false\n".as_bytes())
+            .unwrap()
+            .map(|(index, result)| (index, result.unwrap()))
+            .collect();
+        assert_eq!(
+            yaml,
+            vec![(0, json!({"ok": true})), (1, json!({"ok": false}))]
+        );
+
+        let json5: Vec<_> = parse_stream(Format::Json5, "instance.json5", "{ok: true}".as_bytes())
+            .unwrap()
+            .map(|(index, result)| (index, result.unwrap()))
+            .collect();
+        assert_eq!(json5, vec![(0, json!({"ok": true}))]);
+    }
+
+    /// `--filter` mode hands valid instances back out byte-for-byte, so the
+    /// source text recovered per document has to match the input exactly
+    /// (modulo surrounding whitespace), not a re-serialization of the
+    /// parsed value -- which would reorder keys and renormalize numbers.
+    #[test]
+    fn parse_json_stream_with_source_recovers_exact_source_text() {
+        let input = "  { \"b\": 1, \"a\": 2 }  \n{\"n\": 1.50}\n";
+        let results: Vec<_> = parse_json_stream_with_source("instances.ndjson", input.as_bytes())
+            .map(|(index, result)| {
+                let (value, source) = result.unwrap();
+                (index, value, source)
+            })
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                (0, json!({"b": 1, "a": 2}), "{ \"b\": 1, \"a\": 2 }".to_owned()),
+                (1, json!({"n": 1.50}), "{\"n\": 1.50}".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_json_stream_with_source_attributes_errors_with_document_index() {
+        let err = parse_json_stream_with_source("instances.ndjson", "{}\n[\n".as_bytes())
+            .map(|(index, result)| (index, result.err().map(|err| err.to_string())))
+            .nth(1)
+            .unwrap();
+
+        assert_eq!(err.0, 1);
+        assert!(err.1.unwrap().contains("document 1"));
+    }
+}
@@ -1,12 +1,17 @@
+mod diagnostics;
+mod formats;
+mod parallel;
+
 use anyhow::{Context, Result};
 use clap::{crate_version, load_yaml, App, AppSettings};
+use formats::Format;
 use jtd::{Schema, ValidateOptions};
 use serde::Serialize;
-use serde_json::Deserializer;
+use serde_json::Value;
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{stdin, BufReader, Read};
+use std::io::{stdin, stdout, stderr, BufReader, Read, Write};
 use std::process::exit;
-use std::borrow::Cow;
 
 fn main() -> Result<()> {
     let cli_yaml = load_yaml!("cli.yaml");
@@ -33,60 +38,330 @@ fn main() -> Result<()> {
         0
     };
 
-    let schema_reader = BufReader::new(match matches.value_of("schema").unwrap() {
-        "-" => Box::new(stdin()) as Box<dyn Read>,
-        file @ _ => Box::new(File::open(file)?) as Box<dyn Read>,
-    });
+    let schema_file = matches.value_of("schema").unwrap();
+    let schema_format = resolve_format(matches.value_of("schema-format").unwrap(), schema_file)?;
 
-    let schema = Schema::from_serde_schema(
-        serde_json::from_reader(schema_reader).with_context(|| "Failed to parse schema")?,
-    )
-    .with_context(|| "Malformed schema")?;
+    let schema_reader = open_reader(schema_file)?;
+
+    let schema_value = formats::parse_single(schema_format, schema_file, schema_reader)?;
+    let serde_schema: jtd::SerdeSchema = serde_json::from_value(schema_value)
+        .with_context(|| "Malformed schema")?;
+    let schema = Schema::from_serde_schema(serde_schema).with_context(|| "Malformed schema")?;
 
     schema.validate().with_context(|| "Invalid schema")?;
 
-    let instance_reader = BufReader::new(match matches.value_of("instances").unwrap() {
-        "-" => Box::new(stdin()) as Box<dyn Read>,
-        file @ _ => Box::new(File::open(file)?) as Box<dyn Read>,
-    });
+    let instance_files: Vec<_> = matches.values_of("instances").unwrap().collect();
+    let instance_format_arg = matches.value_of("instance-format").unwrap();
 
-    let stream = Deserializer::from_reader(instance_reader);
-    for instance in stream.into_iter() {
-        let instance = instance.with_context(|| format!("Failed to parse instance"))?;
+    let jobs = if let Some(s) = matches.value_of("jobs") {
+        s.parse()
+            .with_context(|| format!("Failed to parse jobs: {}", s))?
+    } else {
+        1
+    };
 
-        let errors = jtd::validate(
+    let any_invalid = if matches.is_present("filter") {
+        run_filter(
+            &matches,
             &schema,
-            &instance,
-            ValidateOptions::new().with_max_depth(max_depth).with_max_errors(max_errors),
-        )
-        .with_context(|| format!("Failed to validate instance"))?;
+            max_depth,
+            max_errors,
+            instance_format_arg,
+            &instance_files,
+        )?
+    } else if jobs > 1 {
+        run_validate_parallel(
+            &schema,
+            max_depth,
+            max_errors,
+            quiet,
+            jobs,
+            instance_format_arg,
+            &instance_files,
+        )?
+    } else {
+        run_validate(
+            &schema,
+            max_depth,
+            max_errors,
+            quiet,
+            instance_format_arg,
+            &instance_files,
+        )?
+    };
+
+    if any_invalid {
+        exit(1);
+    }
+
+    Ok(())
+}
+
+/// Runs the tool's default mode: validate every instance against `schema`,
+/// printing error indicators (unless `quiet`) and returning whether any
+/// instance was invalid.
+fn run_validate(
+    schema: &Schema,
+    max_depth: usize,
+    max_errors: usize,
+    quiet: bool,
+    instance_format_arg: &str,
+    instance_files: &[&str],
+) -> Result<bool> {
+    let mut any_invalid = false;
+
+    for &file_name in instance_files {
+        let instance_format = resolve_format(instance_format_arg, file_name)?;
+        let instance_reader = open_reader(file_name)?;
+        let stream = formats::parse_stream(instance_format, file_name, instance_reader)?;
+
+        for (instance_index, instance) in stream {
+            let instance = instance?;
+
+            let errors = jtd::validate(
+                schema,
+                &instance,
+                ValidateOptions::new().with_max_depth(max_depth).with_max_errors(max_errors),
+            )
+            .with_context(|| format!("Failed to validate instance from {}", file_name))?;
+
+            if !errors.is_empty() {
+                any_invalid = true;
+
+                if !quiet {
+                    // These are the errors we'll output to the user, using the standard
+                    // JSON Typedef error indicator format.
+                    let error_indicators: Vec<_> = errors
+                        .into_iter()
+                        .map(|err| ErrorIndicator {
+                            file_name: file_name.to_owned(),
+                            instance_index,
+                            instance_path: to_json_pointer(err.instance_path),
+                            schema_path: to_json_pointer(err.schema_path),
+                        })
+                        .collect();
+
+                    for error_indicator in error_indicators {
+                        println!("{}", serde_json::to_string(&error_indicator).unwrap());
+                    }
+                } else {
+                    // Under --quiet, we only care whether anything failed, so
+                    // there's no point reading the rest of this file, or
+                    // opening any of the instance files after it.
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    Ok(any_invalid)
+}
+
+/// Runs `--filter` mode: partitions every instance into a valid stream and
+/// an invalid stream (annotated with error indicators), returning whether
+/// any instance was invalid.
+fn run_filter(
+    matches: &clap::ArgMatches,
+    schema: &Schema,
+    max_depth: usize,
+    max_errors: usize,
+    instance_format_arg: &str,
+    instance_files: &[&str],
+) -> Result<bool> {
+    let mut valid_out = open_writer(matches.value_of("valid-out"), false)?;
+    let mut invalid_out = open_writer(matches.value_of("invalid-out"), true)?;
+
+    let mut any_invalid = false;
+
+    for &file_name in instance_files {
+        let instance_format = resolve_format(instance_format_arg, file_name)?;
+        let instance_reader = open_reader(file_name)?;
+
+        // For JSON/NDJSON we can hand a valid instance back out exactly as
+        // it appeared in the input. YAML and JSON5 instances don't carry
+        // that source text through `formats::parse_stream`, and re-emitting
+        // them as YAML/JSON5 wouldn't be "verbatim" either once comments and
+        // formatting are gone -- so for those two formats we fall back to
+        // writing the parsed value as JSON, same as before.
+        type SourcedInstance = (usize, Result<(Value, String)>);
+        let stream: Box<dyn Iterator<Item = SourcedInstance>> =
+            if instance_format == Format::Json {
+                Box::new(formats::parse_json_stream_with_source(
+                    file_name,
+                    instance_reader,
+                ))
+            } else {
+                Box::new(
+                    formats::parse_stream(instance_format, file_name, instance_reader)?.map(
+                        |(index, result)| {
+                            (
+                                index,
+                                result.map(|instance| {
+                                    let source = serde_json::to_string(&instance).unwrap();
+                                    (instance, source)
+                                }),
+                            )
+                        },
+                    ),
+                )
+            };
+
+        for (instance_index, item) in stream {
+            let (instance, source) = item?;
+
+            let errors = jtd::validate(
+                schema,
+                &instance,
+                ValidateOptions::new().with_max_depth(max_depth).with_max_errors(max_errors),
+            )
+            .with_context(|| format!("Failed to validate instance from {}", file_name))?;
+
+            if errors.is_empty() {
+                writeln!(valid_out, "{}", source)?;
+            } else {
+                any_invalid = true;
 
-        if !errors.is_empty() {
-            if !quiet {
-                // These are the errors we'll output to the user, using the standard
-                // JSON Typedef error indicator format.
                 let error_indicators: Vec<_> = errors
                     .into_iter()
                     .map(|err| ErrorIndicator {
+                        file_name: file_name.to_owned(),
+                        instance_index,
                         instance_path: to_json_pointer(err.instance_path),
                         schema_path: to_json_pointer(err.schema_path),
                     })
                     .collect();
 
-                for error_indicator in error_indicators {
-                    println!("{}", serde_json::to_string(&error_indicator).unwrap());
-                }
+                let invalid_instance = InvalidInstance {
+                    instance,
+                    errors: error_indicators,
+                };
+
+                writeln!(
+                    invalid_out,
+                    "{}",
+                    serde_json::to_string(&invalid_instance).unwrap()
+                )?;
             }
+        }
+    }
+
+    Ok(any_invalid)
+}
+
+/// Runs the default mode, but spreads validation work across `jobs` worker
+/// threads via the [`parallel`] module. Each instance file is still handled
+/// one at a time, in order, but its instances are validated concurrently.
+fn run_validate_parallel(
+    schema: &Schema,
+    max_depth: usize,
+    max_errors: usize,
+    quiet: bool,
+    jobs: usize,
+    instance_format_arg: &str,
+    instance_files: &[&str],
+) -> Result<bool> {
+    let mut any_invalid = false;
+
+    for &file_name in instance_files {
+        let instance_format = resolve_format(instance_format_arg, file_name)?;
+        let instance_reader = open_reader(file_name)?;
 
-            exit(1);
+        // The worker pool needs to send the reader's output across a thread
+        // boundary, so JSON/NDJSON -- the common case for large instance
+        // streams -- is read straight off `serde_json`'s own (`Send`)
+        // `Deserializer` stream, the same way `formats::parse_stream` does.
+        // `serde_yaml::Deserializer`, though, boxes its reader internally as
+        // a non-`Send` trait object, so it can never cross into the worker
+        // threads as a stream; read every YAML/JSON5 document up front
+        // instead; trading the pool's normally-bounded memory for simply
+        // being able to validate the whole file.
+        let stream: Box<dyn Iterator<Item = Result<Value>> + Send> = if instance_format
+            == Format::Json
+        {
+            Box::new(
+                serde_json::Deserializer::from_reader(instance_reader)
+                    .into_iter::<Value>()
+                    .enumerate()
+                    .map(move |(index, result)| {
+                        result.map_err(|err| {
+                            diagnostics::Diagnostic::from_json(file_name, Some(index), &err).into()
+                        })
+                    }),
+            )
+        } else {
+            let instances: Vec<_> =
+                formats::parse_stream(instance_format, file_name, instance_reader)?
+                    .map(|(_, result)| result)
+                    .collect();
+            Box::new(instances.into_iter())
+        };
+
+        let file_invalid = parallel::validate_parallel(
+            schema,
+            max_depth,
+            max_errors,
+            quiet,
+            jobs,
+            stream,
+            |instance_index, indicators| {
+                if !quiet {
+                    for (instance_path, schema_path) in indicators {
+                        let error_indicator = ErrorIndicator {
+                            file_name: file_name.to_owned(),
+                            instance_index,
+                            instance_path: instance_path.to_string(),
+                            schema_path: schema_path.to_string(),
+                        };
+                        println!("{}", serde_json::to_string(&error_indicator).unwrap());
+                    }
+                }
+            },
+        )
+        .with_context(|| format!("Failed to validate instances from {}", file_name))?;
+
+        any_invalid = any_invalid || file_invalid;
+
+        if quiet && any_invalid {
+            // Like the sequential pipeline, there's no point opening any of
+            // the instance files after this one once --quiet has its answer.
+            return Ok(true);
         }
     }
 
-    Ok(())
+    Ok(any_invalid)
+}
+
+fn open_reader(file_name: &str) -> Result<BufReader<Box<dyn Read + Send>>> {
+    Ok(BufReader::new(match file_name {
+        "-" => Box::new(stdin()) as Box<dyn Read + Send>,
+        file => Box::new(File::open(file)?) as Box<dyn Read + Send>,
+    }))
+}
+
+/// Opens a writer for `--valid-out`/`--invalid-out`, falling back to
+/// standard output or standard error when no file is given.
+fn open_writer(file_name: Option<&str>, invalid: bool) -> Result<Box<dyn Write>> {
+    match file_name {
+        Some(file) => Ok(Box::new(File::create(file)?) as Box<dyn Write>),
+        None if invalid => Ok(Box::new(stderr()) as Box<dyn Write>),
+        None => Ok(Box::new(stdout()) as Box<dyn Write>),
+    }
+}
+
+#[derive(Serialize)]
+struct InvalidInstance {
+    instance: Value,
+    errors: Vec<ErrorIndicator>,
 }
 
 #[derive(Serialize)]
 struct ErrorIndicator {
+    #[serde(rename = "fileName")]
+    file_name: String,
+
+    #[serde(rename = "instanceIndex")]
+    instance_index: usize,
+
     #[serde(rename = "instancePath")]
     instance_path: String,
 
@@ -94,7 +369,17 @@ struct ErrorIndicator {
     schema_path: String,
 }
 
-fn to_json_pointer<'a>(path: Vec<Cow<'a, str>>) -> String {
+/// Resolves a `--schema-format`/`--instance-format` value against a file
+/// name, turning "auto" into a concrete `Format`.
+fn resolve_format(format_arg: &str, file_name: &str) -> Result<Format> {
+    if format_arg == "auto" {
+        Ok(Format::from_file_name(file_name))
+    } else {
+        Format::from_name(format_arg)
+    }
+}
+
+pub(crate) fn to_json_pointer<'a>(path: Vec<Cow<'a, str>>) -> String {
     if path.is_empty() {
         "".to_owned()
     } else {
@@ -107,3 +392,99 @@ fn to_json_pointer<'a>(path: Vec<Cow<'a, str>>) -> String {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_schema() -> Schema {
+        Schema::from_serde_schema(
+            serde_json::from_str(r#"{"properties": {"ok": {"type": "boolean"}}}"#).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Validates `instances` one at a time, the way `run_validate` does,
+    /// as the reference behavior the parallel pipeline must match.
+    fn validate_sequentially(
+        schema: &Schema,
+        instances: &[Value],
+    ) -> (bool, Vec<(usize, parallel::Indicators)>) {
+        let mut any_invalid = false;
+        let mut collected = Vec::new();
+
+        for (index, instance) in instances.iter().enumerate() {
+            let errors = jtd::validate(schema, instance, ValidateOptions::new()).unwrap();
+
+            if !errors.is_empty() {
+                any_invalid = true;
+                collected.push((
+                    index,
+                    errors
+                        .into_iter()
+                        .map(|err| (to_json_pointer(err.instance_path), to_json_pointer(err.schema_path)))
+                        .collect(),
+                ));
+            }
+        }
+
+        (any_invalid, collected)
+    }
+
+    #[test]
+    fn parallel_output_matches_sequential_output() {
+        let schema = test_schema();
+        let instances: Vec<Value> = (0..50).map(|i| json!({ "ok": i % 3 != 0 })).collect();
+
+        let (expected_invalid, expected) = validate_sequentially(&schema, &instances);
+
+        for &jobs in &[1, 2, 8] {
+            let mut collected = Vec::new();
+            let any_invalid = parallel::validate_parallel(
+                &schema,
+                0,
+                0,
+                false,
+                jobs,
+                instances.clone().into_iter().map(Ok),
+                |index, indicators| collected.push((index, indicators.clone())),
+            )
+            .unwrap();
+
+            assert_eq!(any_invalid, expected_invalid, "jobs = {}", jobs);
+            assert_eq!(collected, expected, "jobs = {}", jobs);
+        }
+    }
+
+    #[test]
+    fn parallel_pipeline_validates_every_document_in_a_multi_document_yaml_stream() {
+        let schema = test_schema();
+        let yaml = "ok: true\n---\nok: 1\n---\nok: false\n";
+
+        let documents: Vec<Value> = formats::parse_stream(Format::Yaml, "instances.yaml", yaml.as_bytes())
+            .unwrap()
+            .map(|(_, result)| result.unwrap())
+            .collect();
+        assert_eq!(documents.len(), 3);
+
+        let mut collected = Vec::new();
+        let any_invalid = parallel::validate_parallel(
+            &schema,
+            0,
+            0,
+            false,
+            2,
+            documents.into_iter().map(Ok),
+            |index, indicators| collected.push((index, indicators.clone())),
+        )
+        .unwrap();
+
+        // Only the second document (`ok: 1`) fails the schema; if the pool
+        // dropped the YAML stream after its first document, we'd see no
+        // invalid instances at all.
+        assert!(any_invalid);
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].0, 1);
+    }
+}
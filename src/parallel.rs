@@ -0,0 +1,175 @@
+//! A multi-threaded validation pipeline, for validating large instance
+//! streams faster than a single core allows.
+//!
+//! A reader thread pulls parsed instances off the input stream and hands
+//! them to a pool of worker threads, each validating against the same
+//! (immutable, `Sync`) compiled `Schema`. A collector, running on the
+//! calling thread, re-orders the workers' results back into stream order
+//! before handing them to the caller, so output is identical to the
+//! sequential pipeline's, just faster.
+
+use anyhow::Result;
+use jtd::{Schema, ValidateOptions};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The error indicators produced by validating a single instance, as
+/// `(instancePath, schemaPath)` JSON Pointer pairs.
+pub type Indicators = Vec<(String, String)>;
+
+/// Validates `instances` against `schema` using `jobs` worker threads,
+/// invoking `on_result` with the index and error indicators of each
+/// invalid instance, in stream order, as results become available. Valid
+/// instances are not passed to `on_result`.
+///
+/// `max_errors` is applied the same way the sequential pipeline applies
+/// it: as each instance's own `ValidateOptions` budget, not as a cap on
+/// the number of invalid instances. Besides a parse or validation failure,
+/// which stops the pipeline and is returned as an error, the only thing
+/// that stops the stream early is `quiet`, which (like the sequential
+/// pipeline) gives up on the rest of the stream once the first invalid
+/// instance is found. Returns whether any instance was invalid.
+pub fn validate_parallel(
+    schema: &Schema,
+    max_depth: usize,
+    max_errors: usize,
+    quiet: bool,
+    jobs: usize,
+    instances: impl Iterator<Item = Result<Value>> + Send,
+    mut on_result: impl FnMut(usize, &Indicators),
+) -> Result<bool> {
+    // Bound the in-flight queue so a fast reader can't buffer an unbounded
+    // number of instances ahead of a slow worker pool.
+    let queue_bound = jobs * 4;
+    let (work_tx, work_rx) = sync_channel::<(usize, Value)>(queue_bound);
+    let (result_tx, result_rx) = sync_channel::<(usize, Result<Indicators, String>)>(queue_bound);
+    let work_rx = Arc::new(Mutex::new(work_rx));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let mut parse_error = None;
+
+    let any_invalid = thread::scope(|scope| {
+        for _ in 0..jobs {
+            let work_rx = Arc::clone(&work_rx);
+            let result_tx = result_tx.clone();
+
+            scope.spawn(move || loop {
+                let (index, instance) = match work_rx.lock().unwrap().recv() {
+                    Ok(item) => item,
+                    Err(_) => return,
+                };
+
+                let result = jtd::validate(
+                    schema,
+                    &instance,
+                    ValidateOptions::new()
+                        .with_max_depth(max_depth)
+                        .with_max_errors(max_errors),
+                )
+                .map(|errors| {
+                    errors
+                        .into_iter()
+                        .map(|err| {
+                            (
+                                crate::to_json_pointer(err.instance_path),
+                                crate::to_json_pointer(err.schema_path),
+                            )
+                        })
+                        .collect()
+                })
+                .map_err(|err| err.to_string());
+
+                if result_tx.send((index, result)).is_err() {
+                    return;
+                }
+            });
+        }
+
+        // The reader runs on the calling thread's scope too, so that a
+        // failure to parse an instance can stop the whole pipeline without
+        // the collector having to guess why no more results are coming.
+        let reader_stop = Arc::clone(&stop);
+        let reader_handle = scope.spawn(move || {
+            for (index, instance) in instances.enumerate() {
+                if reader_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                match instance {
+                    Ok(instance) => {
+                        if work_tx.send((index, instance)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => return Some((index, err)),
+                }
+            }
+
+            None
+        });
+
+        drop(result_tx);
+
+        let mut any_invalid = false;
+        let mut stopped_early = false;
+        let mut next_index = 0;
+        let mut pending = BTreeMap::new();
+
+        for (index, result) in result_rx.iter() {
+            pending.insert(index, result);
+
+            while let Some(result) = pending.remove(&next_index) {
+                let result_index = next_index;
+                next_index += 1;
+
+                match result {
+                    Ok(indicators) => {
+                        if !indicators.is_empty() {
+                            any_invalid = true;
+
+                            if !stopped_early {
+                                on_result(result_index, &indicators);
+
+                                // Like the sequential pipeline under --quiet,
+                                // we only care whether anything failed, so
+                                // there's no point validating the rest of the
+                                // stream. Unlike --quiet, max_errors is each
+                                // instance's own error budget, not a cap on
+                                // how many invalid instances we report.
+                                if quiet {
+                                    stopped_early = true;
+                                    stop.store(true, Ordering::SeqCst);
+                                }
+                            }
+                        }
+                    }
+                    Err(message) => {
+                        if parse_error.is_none() {
+                            parse_error = Some(message);
+                        }
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        }
+
+        if parse_error.is_none() {
+            parse_error = reader_handle
+                .join()
+                .unwrap_or(None)
+                .map(|(_, err)| err.to_string());
+        }
+
+        any_invalid
+    });
+
+    if let Some(message) = parse_error {
+        anyhow::bail!(message)
+    }
+
+    Ok(any_invalid)
+}